@@ -3,8 +3,13 @@ use crate::utils::FileStats;
 use anyhow::{anyhow, Result};
 use ignore::{DirEntry, WalkParallel, WalkState};
 
+use std::collections::HashMap;
+use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::sync::{mpsc, Arc};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, Once, OnceLock};
+use std::time::{Duration, SystemTime};
 
 /// A trait to abstract how ast-grep discovers work Items.
 ///
@@ -23,6 +28,28 @@ pub trait Worker: Sync + Send {
   fn consume_items(&self, items: Items<Self::Item>) -> Result<()>;
 }
 
+/// Controls whether `run_worker` emits items in a deterministic order.
+///
+/// `WalkParallel` discovers files on many threads, so by default items
+/// arrive in whatever order parsing/matching happens to finish, which
+/// hurts scriptability, golden tests and diffing. Picking a `SortMode`
+/// other than `None` buffers items for a short, bounded deadline so they
+/// can be sorted before being handed to the consumer; see `run_worker`.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortMode {
+  /// Emit items as soon as they are produced. Fastest, but unordered.
+  #[default]
+  None,
+  /// Sort by file path.
+  Path,
+  /// Sort by last modified time.
+  Modified,
+  /// Sort by creation time.
+  Created,
+  /// Sort by last accessed time.
+  Accessed,
+}
+
 /// A trait to abstract how ast-grep discovers, parses and processes files.
 ///
 /// It follows multiple-producer-single-consumer pattern.
@@ -36,6 +63,42 @@ pub trait PathWorker: Worker {
   fn get_stats(&self) -> &FileStats;
   /// Parse and find_match can be done in `produce_item`.
   fn produce_item(&self, path: &Path) -> Option<Vec<Self::Item>>;
+  /// Like `produce_item`, but called instead of it when a `FileDecoder` has
+  /// already decoded the file: `content` is the decoded source, and `path`
+  /// is still the *original* file path (e.g. `logs.rs.gz`, not a tempfile)
+  /// so produced `Item`s can report a meaningful source location. `content`
+  /// is `None` when no decoder matched `path`, meaning the default falls
+  /// back to reading `path` from disk via `produce_item`. Implementations
+  /// that set `decoder` should override this to parse `content` directly.
+  fn produce_item_from(&self, path: &Path, content: Option<String>) -> Option<Vec<Self::Item>> {
+    let _ = content;
+    self.produce_item(path)
+  }
+  /// Controls the order items are handed to `consume_items`.
+  /// Defaults to `SortMode::None`, i.e. fully parallel and unordered.
+  fn sort_mode(&self) -> SortMode {
+    SortMode::None
+  }
+  /// Optional decoder used to transparently decompress or preprocess a
+  /// file's content before it reaches `produce_item_from`, e.g. to search inside
+  /// `.gz`/`.bz2`/`.xz`/`.zst` archives. Opt-in: returns `None` by default,
+  /// so the raw filesystem walk is unaffected.
+  fn decoder(&self) -> Option<&FileDecoder> {
+    None
+  }
+  /// How many parsed items may sit in the channel before a producer blocks
+  /// on `send`. Bounds memory when parsing/matching outpaces the consumer.
+  /// Defaults to twice the available parallelism; override to trade memory
+  /// for throughput.
+  fn channel_bound(&self) -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get()) * 2
+  }
+  /// Restricts which discovered entries are processed at all, following
+  /// fd's type filtering. Defaults to `FileTypeFilter::File`, preserving
+  /// today's behavior of skipping symlinks and directories.
+  fn type_filter(&self) -> FileTypeFilter {
+    FileTypeFilter::default()
+  }
 
   fn run_path(self) -> Result<()>
   where
@@ -43,6 +106,55 @@ pub trait PathWorker: Worker {
   {
     run_worker(Arc::new(self))
   }
+
+  /// Consumes the paths produced by `run_files`. Receives them through the
+  /// same `Items` type as `consume_items`, so an override can also stop the
+  /// walk early with `items.cancel()`; `run_files` records no `FileStats`.
+  /// Defaults to printing each path, mirroring ripgrep's `--files`.
+  fn consume_paths(&self, paths: Items<PathBuf>) -> Result<()> {
+    for path in paths {
+      println!("{}", path.display());
+    }
+    Ok(())
+  }
+
+  /// Runs the same parallel walk as `run_path`, but skips `produce_item`
+  /// and parsing entirely and hands every discovered (and `type_filter`ed)
+  /// path to `consume_paths` instead. Mirrors ripgrep's `--files`: lets
+  /// users preview exactly which files a given ignore/type configuration
+  /// will scan, or pipe the file list into another tool.
+  fn run_files(&self) -> Result<()> {
+    let (tx, rx) = mpsc::sync_channel(self.channel_bound());
+    let walker = self.build_walk()?;
+    let type_filter = self.type_filter();
+    let cancel = Cancellation::new();
+    let walker_cancel = cancel.clone();
+    // Mirror run_worker: discovery runs on its own thread so paths reach
+    // consume_paths as they're found instead of only after the whole walk
+    // finishes, and the closure shares `cancel` with the `Items` below so
+    // `items.cancel()` from the consumer actually stops the walk.
+    std::thread::spawn(move || {
+      let tx = tx;
+      walker.run(|| {
+        let tx = tx.clone();
+        let type_filter = type_filter.clone();
+        let cancel = walker_cancel.clone();
+        Box::new(move |result| {
+          if cancel.is_cancelled() {
+            return WalkState::Quit;
+          }
+          let Some(entry) = filter_result(result, SortMode::None, &type_filter) else {
+            return WalkState::Continue;
+          };
+          match tx.send(entry.path) {
+            Ok(_) => WalkState::Continue,
+            Err(_) => WalkState::Quit,
+          }
+        })
+      });
+    });
+    self.consume_paths(Items { rx, cancel })
+  }
 }
 
 pub trait StdInWorker: Worker {
@@ -58,11 +170,34 @@ pub trait StdInWorker: Worker {
   }
 }
 
-pub struct Items<T>(mpsc::Receiver<T>);
+/// A flag shared between producers and a consumer so a scan can be stopped
+/// early, e.g. by a `--max-count`-style consumer or a Ctrl-C handler.
+/// Cloning shares the same underlying flag.
+#[derive(Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Signal producers to stop as soon as they next check.
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+  /// Whether `cancel` has been called.
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+pub struct Items<T> {
+  rx: mpsc::Receiver<T>,
+  cancel: Cancellation,
+}
 impl<T> Iterator for Items<T> {
   type Item = T;
   fn next(&mut self) -> Option<Self::Item> {
-    if let Ok(match_result) = self.0.recv() {
+    if let Ok(match_result) = self.rx.recv() {
       Some(match_result)
     } else {
       None
@@ -77,11 +212,189 @@ impl<T> Items<T> {
       Ok(_) => (),
       Err(e) => return Err(anyhow!(e.to_string())),
     };
-    Ok(Items(rx))
+    Ok(Items {
+      rx,
+      cancel: Cancellation::new(),
+    })
+  }
+  /// Signal producers to stop discovering/parsing further files, e.g. once
+  /// a `--max-count`-style consumer has seen enough matches.
+  pub fn cancel(&self) {
+    self.cancel.cancel();
+  }
+}
+
+/// Default cap on how much decoded output `FileDecoder::decode` will buffer
+/// for a single file, to guard against decompression bombs: a tiny
+/// compressed input expanding to gigabytes of output.
+const DEFAULT_MAX_DECODED_SIZE: u64 = 100 * 1024 * 1024; // 100 MiB
+
+/// Decodes a file's content before it is parsed, by spawning an external
+/// decompressor or preprocessor and reading its stdout. Which command runs
+/// is chosen by the file's extension, e.g. registering `"gz"` lets rules
+/// transparently search inside `.gz` archives.
+pub struct FileDecoder {
+  commands: HashMap<String, Vec<String>>,
+  max_decoded_size: u64,
+}
+
+impl FileDecoder {
+  /// An empty decoder; use `with_command` to register extensions.
+  pub fn new() -> Self {
+    Self {
+      commands: HashMap::new(),
+      max_decoded_size: DEFAULT_MAX_DECODED_SIZE,
+    }
+  }
+
+  /// Override the decoded-size cap (100 MiB by default) that guards against
+  /// decompression bombs; `decode` errors out once a command's stdout
+  /// exceeds this many bytes instead of buffering all of it.
+  pub fn with_max_decoded_size(mut self, bytes: u64) -> Self {
+    self.max_decoded_size = bytes;
+    self
+  }
+
+  /// Decoder pre-populated for the common compression formats mentioned in
+  /// the `.gz`/`.bz2`/`.xz`/`.zst` use case, using the matching CLI tool.
+  pub fn with_common_archives() -> Self {
+    Self::new()
+      .with_command("gz", ["gzip", "-dc"])
+      .with_command("bz2", ["bzip2", "-dc"])
+      .with_command("xz", ["xz", "-dc"])
+      .with_command("zst", ["zstd", "-dc"])
+  }
+
+  /// Register the command used to decode files with the given extension
+  /// (without the leading dot). The file path is appended as the last
+  /// argument, and the command's stdout becomes the decoded content.
+  pub fn with_command<I, S>(mut self, ext: &str, command: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    self
+      .commands
+      .insert(ext.to_string(), command.into_iter().map(Into::into).collect());
+    self
+  }
+
+  /// Run the registered command for `path`'s extension, if any, and return
+  /// its decoded output. `Ok(None)` means no command matched, so the caller
+  /// should fall back to parsing the raw file. Stdout is capped at
+  /// `max_decoded_size` bytes so a crafted small archive can't OOM the
+  /// process by decompressing to an unbounded size.
+  fn decode(&self, path: &Path) -> Result<Option<String>> {
+    let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+      return Ok(None);
+    };
+    let Some(command) = self.commands.get(ext) else {
+      return Ok(None);
+    };
+    let [program, args @ ..] = command.as_slice() else {
+      return Ok(None);
+    };
+    let mut child = Command::new(program)
+      .args(args)
+      .arg(path)
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped())
+      .spawn()?;
+    let mut stdout = child.stdout.take().expect("child stdout was piped");
+    let mut stderr = child.stderr.take().expect("child stderr was piped");
+    // Read stderr on its own thread: both pipes are now piped, and reading
+    // stdout to completion first would block forever if the child fills the
+    // stderr pipe buffer before exiting.
+    let stderr_reader = std::thread::spawn(move || {
+      let mut buf = Vec::new();
+      let _ = stderr.read_to_end(&mut buf);
+      buf
+    });
+    let mut buf = Vec::new();
+    let read = stdout
+      .by_ref()
+      .take(self.max_decoded_size + 1)
+      .read_to_end(&mut buf)?;
+    if read as u64 > self.max_decoded_size {
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(anyhow!(
+        "`{}` produced more than {} decoded bytes for {} (possible decompression bomb)",
+        program,
+        self.max_decoded_size,
+        path.display()
+      ));
+    }
+    let status = child.wait()?;
+    let stderr_output = stderr_reader.join().unwrap_or_default();
+    if !status.success() {
+      return Err(anyhow!(
+        "`{}` exited with {}: {}",
+        program,
+        status,
+        String::from_utf8_lossy(&stderr_output).trim()
+      ));
+    }
+    Ok(Some(String::from_utf8(buf)?))
+  }
+}
+
+impl Default for FileDecoder {
+  fn default() -> Self {
+    Self::new()
   }
 }
 
-fn filter_result(result: Result<DirEntry, ignore::Error>) -> Option<PathBuf> {
+/// Produce items for `path`, running it through the worker's `FileDecoder`
+/// first if one is configured and matches. `path` is always passed through
+/// unchanged to `produce_item_from`, even when decoded, so produced `Item`s
+/// can still report which archive a match came from rather than a
+/// since-deleted tempfile path. A decode failure is reported through the
+/// usual `eprintln!` channel and treated as a skip, not a hard error, so
+/// one bad archive doesn't abort the whole scan.
+fn produce_item<W: PathWorker + ?Sized>(worker: &W, path: &Path) -> Option<Vec<W::Item>> {
+  let Some(decoder) = worker.decoder() else {
+    return worker.produce_item(path);
+  };
+  match decoder.decode(path) {
+    Ok(content) => worker.produce_item_from(path, content),
+    Err(err) => {
+      eprintln!("ERROR: failed to decode {}: {}", path.display(), err);
+      None
+    }
+  }
+}
+
+/// Restricts which discovered entries `filter_result` accepts, mirroring
+/// fd's type filtering. This decides *whether* an entry is processed at
+/// all; it doesn't replace per-language parsing in `produce_item`.
+#[derive(Clone, Default)]
+pub enum FileTypeFilter {
+  /// Regular files only; skips symlinks and directories. Default.
+  #[default]
+  File,
+  /// Symlinks only.
+  Symlink,
+  /// Directories only, fd's `--type d` equivalent.
+  Directory,
+  /// Only files whose extension is in this named set, matched
+  /// case-insensitively, e.g. a language's registered extensions
+  /// (`vec!["ts".into(), "tsx".into()]` for TypeScript).
+  Extension(Vec<String>),
+}
+
+/// A discovered file path, with the timestamp `SortMode` needs to order it
+/// by, if any. Statting happens here so the consumer never has to re-stat.
+struct WalkEntry {
+  path: PathBuf,
+  time: Option<SystemTime>,
+}
+
+fn filter_result(
+  result: Result<DirEntry, ignore::Error>,
+  sort_mode: SortMode,
+  type_filter: &FileTypeFilter,
+) -> Option<WalkEntry> {
   let entry = match result {
     Ok(entry) => entry,
     Err(err) => {
@@ -89,46 +402,381 @@ fn filter_result(result: Result<DirEntry, ignore::Error>) -> Option<PathBuf> {
       return None;
     }
   };
-  if !entry.file_type()?.is_file() {
+  let file_type = entry.file_type()?;
+  let accepted = match type_filter {
+    FileTypeFilter::File => file_type.is_file(),
+    FileTypeFilter::Symlink => file_type.is_symlink(),
+    FileTypeFilter::Directory => file_type.is_dir(),
+    FileTypeFilter::Extension(exts) => {
+      file_type.is_file()
+        && entry
+          .path()
+          .extension()
+          .and_then(|e| e.to_str())
+          .is_some_and(|e| exts.iter().any(|allowed| allowed.eq_ignore_ascii_case(e)))
+    }
+  };
+  if !accepted {
     return None;
   }
+  let time = match sort_mode {
+    SortMode::Modified => entry.metadata().ok().and_then(|m| m.modified().ok()),
+    SortMode::Created => entry.metadata().ok().and_then(|m| m.created().ok()),
+    SortMode::Accessed => entry.metadata().ok().and_then(|m| m.accessed().ok()),
+    SortMode::None | SortMode::Path => None,
+  };
   let path = entry.into_path();
   // TODO: is it correct here? see https://github.com/ast-grep/ast-grep/issues/1343
-  match path.strip_prefix("./") {
-    Ok(p) => Some(p.to_path_buf()),
-    Err(_) => Some(path),
+  let path = match path.strip_prefix("./") {
+    Ok(p) => p.to_path_buf(),
+    Err(_) => path,
+  };
+  Some(WalkEntry { path, time })
+}
+
+/// Sort key for buffered items. All entries buffered during a single run
+/// share one `SortMode`, so only one variant is ever compared against
+/// another of the same kind.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+  Path(PathBuf),
+  Time(SystemTime, PathBuf),
+}
+
+impl SortKey {
+  fn new(sort_mode: SortMode, entry: &WalkEntry) -> Self {
+    match sort_mode {
+      SortMode::None | SortMode::Path => SortKey::Path(entry.path.clone()),
+      SortMode::Modified | SortMode::Created | SortMode::Accessed => {
+        // fall back to UNIX_EPOCH if stat-ing the timestamp failed, and keep
+        // path as a stable tiebreak for files sharing the same timestamp.
+        SortKey::Time(entry.time.unwrap_or(SystemTime::UNIX_EPOCH), entry.path.clone())
+      }
+    }
   }
 }
 
+/// Deadline before a `Buffering` consumer gives up on global ordering and
+/// starts forwarding items as soon as they arrive.
+const SORT_DEADLINE: Duration = Duration::from_millis(100);
+
+/// Two-state buffering for `SortMode`, modeled on fd's deterministic output.
+///
+/// The consumer starts `Buffering`, collecting every produced item (plus its
+/// sort key) while a deadline timer runs. If discovery finishes before the
+/// deadline, the whole buffer is sorted and flushed in order. If the
+/// deadline fires first, the buffer is sorted "so far" and flushed, then the
+/// state flips to `Streaming` so later items are forwarded immediately,
+/// trading full ordering for bounded memory and latency.
+enum SortState<T> {
+  Buffering(Vec<(SortKey, Vec<T>)>),
+  Streaming,
+}
+
+/// `SortState` plus a `Condvar` so the deadline timer can be woken early
+/// instead of always sleeping the full `SORT_DEADLINE`, and so it notices
+/// promptly when discovery already flushed the buffer.
+struct SortCoordinator<T> {
+  state: Mutex<SortState<T>>,
+  done: Condvar,
+}
+
+impl<T> SortCoordinator<T> {
+  fn new() -> Self {
+    Self {
+      state: Mutex::new(SortState::Buffering(Vec::new())),
+      done: Condvar::new(),
+    }
+  }
+}
+
+/// Sort and send everything buffered so far, then switch to `Streaming` and
+/// wake anyone (the deadline timer) waiting on `done`. No-op if the state
+/// has already been flushed by the other race (the deadline timer vs.
+/// discovery finishing).
+///
+/// The buffer is drained into a local `Vec` while the lock is held, and
+/// `tx.send` only runs after the lock is dropped: `tx` is a bounded
+/// (chunk0-4) channel, so `send` can block once the consumer falls behind,
+/// and holding the lock across that would stall every concurrent
+/// `WalkParallel` worker trying to acquire it to buffer its own item.
+fn flush_buffer<T>(coordinator: &SortCoordinator<T>, tx: &mpsc::SyncSender<T>) {
+  let mut guard = coordinator.state.lock().unwrap();
+  let drained = if let SortState::Buffering(buf) = &mut *guard {
+    buf.sort_by(|a, b| a.0.cmp(&b.0));
+    let drained: Vec<T> = buf.drain(..).flat_map(|(_, items)| items).collect();
+    *guard = SortState::Streaming;
+    Some(drained)
+  } else {
+    None
+  };
+  drop(guard);
+  coordinator.done.notify_all();
+  if let Some(drained) = drained {
+    for item in drained {
+      let _ = tx.send(item);
+    }
+  }
+}
+
+/// The `Cancellation` that the process-wide Ctrl-C handler should flip.
+/// `ctrlc::set_handler` can only be installed once per process, so rather
+/// than reinstalling it on every `run_worker` call (which would silently
+/// fail from the second call on), we install it exactly once and have it
+/// read whichever `Cancellation` the current run last registered here.
+static CTRLC_CANCEL: OnceLock<Mutex<Option<Cancellation>>> = OnceLock::new();
+static CTRLC_INIT: Once = Once::new();
+
+/// Point the process-wide Ctrl-C handler at `cancel`, installing the
+/// handler itself on first use. Safe to call repeatedly across separate
+/// `run_worker` invocations in the same process.
+fn install_ctrlc_cancel(cancel: Cancellation) {
+  let slot = CTRLC_CANCEL.get_or_init(|| Mutex::new(None));
+  *slot.lock().unwrap() = Some(cancel);
+  CTRLC_INIT.call_once(|| {
+    let _ = ctrlc::set_handler(|| {
+      if let Some(cancel) = CTRLC_CANCEL.get().and_then(|slot| slot.lock().unwrap().clone()) {
+        cancel.cancel();
+      }
+    });
+  });
+}
+
 fn run_worker<W: PathWorker + ?Sized + 'static>(worker: Arc<W>) -> Result<()> {
-  let (tx, rx) = mpsc::channel();
+  // Bounded: a producer blocks on `send` once the consumer falls behind,
+  // instead of piling up unbounded heavy tree-sitter `Item`s in memory.
+  let (tx, rx) = mpsc::sync_channel(worker.channel_bound());
+  let sort_mode = worker.sort_mode();
+  let type_filter = worker.type_filter();
   let w = worker.clone();
   let walker = worker.build_walk()?;
+  let coordinator = Arc::new(SortCoordinator::new());
+  let cancel = Cancellation::new();
+  // Best-effort: let Ctrl-C flip the flag so producers wind down and the
+  // consumer can flush partial output/stats, instead of the process dying
+  // mid-write.
+  install_ctrlc_cancel(cancel.clone());
+  if sort_mode != SortMode::None {
+    // Deadline timer: flush whatever is buffered so far once discovery is
+    // taking too long. `wait_timeout_while` wakes as soon as the finish
+    // path below flushes and notifies `done`, so a fast scan isn't held up
+    // for the full deadline just to drop this thread's `tx` clone.
+    let coordinator = coordinator.clone();
+    let tx = tx.clone();
+    std::thread::spawn(move || {
+      let guard = coordinator.state.lock().unwrap();
+      let (guard, _timed_out) = coordinator
+        .done
+        .wait_timeout_while(guard, SORT_DEADLINE, |s| matches!(s, SortState::Buffering(_)))
+        .unwrap();
+      drop(guard);
+      flush_buffer(&coordinator, &tx);
+    });
+  }
+  let consumer_cancel = cancel.clone();
   // walker run will block the thread
   std::thread::spawn(move || {
     let tx = tx;
+    let coordinator = coordinator;
     walker.run(|| {
       let tx = tx.clone();
       let w = w.clone();
+      let coordinator = coordinator.clone();
+      let cancel = cancel.clone();
+      let type_filter = type_filter.clone();
       Box::new(move |result| {
-        let Some(p) = filter_result(result) else {
+        if cancel.is_cancelled() {
+          return WalkState::Quit;
+        }
+        let Some(entry) = filter_result(result, sort_mode, &type_filter) else {
           return WalkState::Continue;
         };
         let stats = w.get_stats();
         stats.add_scanned();
-        let Some(items) = w.produce_item(&p) else {
+        let Some(items) = produce_item(&*w, &entry.path) else {
           stats.add_skipped();
           return WalkState::Continue;
         };
-        for result in items {
-          match tx.send(result) {
-            Ok(_) => continue,
-            Err(_) => return WalkState::Quit,
+        if sort_mode == SortMode::None {
+          for result in items {
+            match tx.send(result) {
+              Ok(_) => continue,
+              Err(_) => return WalkState::Quit,
+            }
+          }
+          return WalkState::Continue;
+        }
+        let mut guard = coordinator.state.lock().unwrap();
+        match &mut *guard {
+          SortState::Buffering(buf) => buf.push((SortKey::new(sort_mode, &entry), items)),
+          SortState::Streaming => {
+            drop(guard);
+            for item in items {
+              if tx.send(item).is_err() {
+                return WalkState::Quit;
+              }
+            }
           }
         }
         WalkState::Continue
       })
     });
+    // Discovery finished: if we beat the deadline, sort and flush now so
+    // output is fully deterministic instead of only "sorted so far", and
+    // wake the timer thread above so it can drop its `tx` clone right away.
+    flush_buffer(&coordinator, &tx);
   });
-  worker.consume_items(Items(rx))
-}
\ No newline at end of file
+  worker.consume_items(Items {
+    rx,
+    cancel: consumer_cancel,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ignore::WalkBuilder;
+  use std::fs;
+
+  fn entries_in(dir: &Path) -> Vec<Result<DirEntry, ignore::Error>> {
+    WalkBuilder::new(dir)
+      .build()
+      .filter(|r| match r {
+        // skip the root entry itself, only interested in its children
+        Ok(e) => e.depth() > 0,
+        Err(_) => true,
+      })
+      .collect()
+  }
+
+  fn unique_temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ast-grep-worker-test-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  #[test]
+  fn filter_result_respects_type_filter() {
+    let dir = unique_temp_dir("type-filter");
+    fs::write(dir.join("a.ts"), "").unwrap();
+    fs::write(dir.join("a.rs"), "").unwrap();
+    fs::create_dir(dir.join("subdir")).unwrap();
+
+    let file_paths: Vec<_> = entries_in(&dir)
+      .into_iter()
+      .filter_map(|r| filter_result(r, SortMode::None, &FileTypeFilter::File))
+      .map(|e| e.path)
+      .collect();
+    assert_eq!(file_paths.len(), 2);
+
+    let dir_paths: Vec<_> = entries_in(&dir)
+      .into_iter()
+      .filter_map(|r| filter_result(r, SortMode::None, &FileTypeFilter::Directory))
+      .map(|e| e.path)
+      .collect();
+    assert_eq!(dir_paths, vec![dir.join("subdir")]);
+
+    let ts_paths: Vec<_> = entries_in(&dir)
+      .into_iter()
+      .filter_map(|r| filter_result(r, SortMode::None, &FileTypeFilter::Extension(vec!["TS".into()])))
+      .map(|e| e.path)
+      .collect();
+    assert_eq!(ts_paths, vec![dir.join("a.ts")]);
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn sort_key_orders_by_path() {
+    let a = WalkEntry {
+      path: PathBuf::from("a.rs"),
+      time: None,
+    };
+    let b = WalkEntry {
+      path: PathBuf::from("b.rs"),
+      time: None,
+    };
+    let key_a = SortKey::new(SortMode::Path, &a);
+    let key_b = SortKey::new(SortMode::Path, &b);
+    assert!(key_a < key_b);
+  }
+
+  #[test]
+  fn sort_key_orders_by_time_then_path_tiebreak() {
+    use std::time::Duration as StdDuration;
+    let epoch = SystemTime::UNIX_EPOCH;
+    let earlier = WalkEntry {
+      path: PathBuf::from("z.rs"),
+      time: Some(epoch),
+    };
+    let later = WalkEntry {
+      path: PathBuf::from("a.rs"),
+      time: Some(epoch + StdDuration::from_secs(1)),
+    };
+    assert!(SortKey::new(SortMode::Modified, &earlier) < SortKey::new(SortMode::Modified, &later));
+
+    // same timestamp: path is the stable tiebreak
+    let first = WalkEntry {
+      path: PathBuf::from("a.rs"),
+      time: Some(epoch),
+    };
+    let second = WalkEntry {
+      path: PathBuf::from("b.rs"),
+      time: Some(epoch),
+    };
+    assert!(SortKey::new(SortMode::Modified, &first) < SortKey::new(SortMode::Modified, &second));
+  }
+
+  #[test]
+  fn flush_buffer_sorts_then_streams_and_is_idempotent() {
+    let coordinator = SortCoordinator::new();
+    let (tx, rx) = mpsc::sync_channel::<i32>(10);
+    {
+      let mut guard = coordinator.state.lock().unwrap();
+      if let SortState::Buffering(buf) = &mut *guard {
+        buf.push((SortKey::Path(PathBuf::from("b")), vec![2]));
+        buf.push((SortKey::Path(PathBuf::from("a")), vec![1]));
+      }
+    }
+    flush_buffer(&coordinator, &tx);
+    assert_eq!(rx.recv().unwrap(), 1);
+    assert_eq!(rx.recv().unwrap(), 2);
+
+    // a second flush after the state already flipped to `Streaming` must
+    // be a no-op: nothing further should be sent on `tx`.
+    flush_buffer(&coordinator, &tx);
+    drop(tx);
+    assert!(rx.recv().is_err());
+  }
+
+  #[test]
+  fn deadline_timer_does_not_block_past_an_early_finish() {
+    // Simulates the finish-path racing the deadline timer: the timer
+    // should observe the early flush via the condvar and return almost
+    // immediately rather than sleeping the full `SORT_DEADLINE`.
+    let coordinator = Arc::new(SortCoordinator::<i32>::new());
+    let (tx, _rx) = mpsc::sync_channel::<i32>(10);
+
+    let timer_coordinator = coordinator.clone();
+    let start = std::time::Instant::now();
+    let timer = std::thread::spawn(move || {
+      let guard = timer_coordinator.state.lock().unwrap();
+      let (_guard, timed_out) = timer_coordinator
+        .done
+        .wait_timeout_while(guard, SORT_DEADLINE, |s| matches!(s, SortState::Buffering(_)))
+        .unwrap();
+      timed_out.timed_out()
+    });
+
+    // "discovery" finishes almost immediately, well before SORT_DEADLINE.
+    flush_buffer(&coordinator, &tx);
+
+    let timed_out = timer.join().unwrap();
+    assert!(!timed_out, "timer should have woken via notify, not timeout");
+    assert!(
+      start.elapsed() < SORT_DEADLINE,
+      "timer thread took too long to notice the early flush"
+    );
+  }
+}